@@ -3,6 +3,8 @@ use anyhow::anyhow;
 use radicle::cob::patch;
 use radicle::cob::patch::RevisionId;
 use radicle::git::RefString;
+use radicle::node::events::CorrelationId;
+use radicle::node::fetch;
 use radicle::storage::git::Repository;
 use radicle::storage::ReadRepository;
 use radicle::{git, rad};
@@ -13,7 +15,17 @@ pub fn run(
     revision_id: &RevisionId,
     stored: &Repository,
     working: &git::raw::Repository,
+    worker: Option<&fetch::Worker>,
 ) -> anyhow::Result<()> {
+    let correlation_id = CorrelationId::new();
+    let span = tracing::info_span!(
+        "checkout",
+        correlation_id = %correlation_id,
+        rid = %stored.id(),
+        revision_id = %revision_id,
+    );
+    let _enter = span.enter();
+
     let patches = patch::Patches::open(stored)?;
 
     let (patch_id, patch, _, revision) = patches
@@ -29,26 +41,31 @@ pub fn run(
     } else {
         &revision
     };
+    tracing::debug!(patch_id = %patch_id, "resolved patch revision");
 
     let mut spinner = term::spinner("Performing checkout...");
     let patch_branch =
         // SAFETY: Patch IDs are valid refstrings.
         git::refname!("patch").join(RefString::try_from(term::format::cob(&patch_id)).unwrap());
 
-    match working.find_branch(patch_branch.as_str(), radicle::git::raw::BranchType::Local) {
-        Ok(branch) => {
-            let commit = branch.get().peel_to_commit()?;
-            working.checkout_tree(commit.as_object(), None)?;
-        }
-        Err(e) if radicle::git::is_not_found_err(&e) => {
-            let commit = find_patch_commit(revision, stored, working)?;
-            // Create patch branch and switch to it.
-            working.branch(patch_branch.as_str(), &commit, true)?;
-            working.checkout_tree(commit.as_object(), None)?;
+    {
+        let _enter = tracing::info_span!("fetch_and_branch", patch_id = %patch_id).entered();
+
+        match working.find_branch(patch_branch.as_str(), radicle::git::raw::BranchType::Local) {
+            Ok(branch) => {
+                let commit = branch.get().peel_to_commit()?;
+                working.checkout_tree(commit.as_object(), None)?;
+            }
+            Err(e) if radicle::git::is_not_found_err(&e) => {
+                let commit = find_patch_commit(revision, stored, working, correlation_id, worker)?;
+                // Create patch branch and switch to it.
+                working.branch(patch_branch.as_str(), &commit, true)?;
+                working.checkout_tree(commit.as_object(), None)?;
+            }
+            Err(e) => return Err(e.into()),
         }
-        Err(e) => return Err(e.into()),
+        working.set_head(&git::refs::workdir::branch(&patch_branch))?;
     }
-    working.set_head(&git::refs::workdir::branch(&patch_branch))?;
 
     spinner.message(format!(
         "Switched to branch {}",
@@ -56,25 +73,35 @@ pub fn run(
     ));
     spinner.finish();
 
-    if let Some(branch) = rad::setup_patch_upstream(&patch_id, revision.head(), working, false)? {
-        let tracking = branch
-            .name()?
-            .ok_or_else(|| anyhow!("failed to create tracking branch: invalid name"))?;
-        term::success!(
-            "Branch {} setup to track {}",
-            term::format::highlight(patch_branch),
-            term::format::tertiary(tracking)
-        );
+    {
+        let _enter = tracing::info_span!("upstream_tracking", patch_id = %patch_id).entered();
+
+        if let Some(branch) = rad::setup_patch_upstream(&patch_id, revision.head(), working, false)?
+        {
+            let tracking = branch
+                .name()?
+                .ok_or_else(|| anyhow!("failed to create tracking branch: invalid name"))?;
+            tracing::info!(remote = tracking, "set up upstream tracking branch");
+            term::success!(
+                "Branch {} setup to track {}",
+                term::format::highlight(patch_branch),
+                term::format::tertiary(tracking)
+            );
+        }
     }
     Ok(())
 }
 
 /// Try to find the patch head in our working copy, and if we don't find it,
-/// fetch it from storage first.
-fn find_patch_commit<'a>(
+/// fetch it from storage first. When `worker` is given, the fetch is enqueued on the
+/// background fetch worker instead of running inline, so concurrent checkouts don't
+/// burst storage with simultaneous fetches.
+pub(super) fn find_patch_commit<'a>(
     revision: &patch::Revision,
     stored: &Repository,
     working: &'a git::raw::Repository,
+    correlation_id: CorrelationId,
+    worker: Option<&fetch::Worker>,
 ) -> anyhow::Result<git::raw::Commit<'a>> {
     let head = *revision.head();
 
@@ -82,12 +109,39 @@ fn find_patch_commit<'a>(
         Ok(commit) => Ok(commit),
         Err(e) if git::ext::is_not_found_err(&e) => {
             let url = git::url::File::new(stored.path());
+            let _enter = tracing::info_span!(
+                "fetch",
+                correlation_id = %correlation_id,
+                rid = %stored.id(),
+                head = %head,
+                remote = %url,
+            )
+            .entered();
+            tracing::info!("patch head missing locally, fetching from storage");
 
-            working.remote_anonymous(url.to_string().as_str())?.fetch(
-                &[head.to_string()],
-                None,
-                None,
-            )?;
+            match worker {
+                Some(worker) => {
+                    let handle = worker.enqueue(fetch::Request {
+                        rid: stored.id(),
+                        working: working.path().to_owned(),
+                        url: url.to_string(),
+                        oids: vec![head],
+                    });
+                    tracing::debug!(
+                        queue_depth = worker.depth(),
+                        sleep_interval_ms = worker.sleep_interval().as_millis() as u64,
+                        "enqueued fetch"
+                    );
+                    handle.wait()?;
+                }
+                None => {
+                    working.remote_anonymous(url.to_string().as_str())?.fetch(
+                        &[head.to_string()],
+                        None,
+                        None,
+                    )?;
+                }
+            }
             working.find_commit(head).map_err(|e| e.into())
         }
         Err(e) => Err(e.into()),
@@ -1,6 +1,8 @@
 use super::*;
 
+use radicle::node::events::{Emitter, Event};
 use radicle::storage::git::Repository;
+use radicle::storage::ReadRepository;
 
 use crate::terminal as term;
 
@@ -10,6 +12,7 @@ pub fn run(
     remove: BTreeSet<Label>,
     profile: &Profile,
     repository: &Repository,
+    emitter: Option<&Emitter<Event>>,
 ) -> anyhow::Result<()> {
     let signer = term::signer(profile)?;
     let mut patches = radicle::cob::patch::Patches::open(repository)?;
@@ -22,6 +25,14 @@ pub fn run(
         .chain(add.iter())
         .cloned()
         .collect::<Vec<_>>();
-    patch.label(labels, &signer)?;
+    patch.label(labels.clone(), &signer)?;
+
+    if let Some(emitter) = emitter {
+        emitter.emit(Event::Labeled {
+            rid: repository.id(),
+            patch_id: *patch_id,
+            labels,
+        });
+    }
     Ok(())
 }
@@ -3,12 +3,14 @@ pub mod upload_pack;
 pub use upload_pack::UploadPack;
 
 use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::time;
 
 use crossbeam_channel as chan;
 
+use crate::cob;
 use crate::git::Oid;
 use crate::node;
 use crate::prelude::*;
@@ -17,6 +19,30 @@ use crate::storage::{refs, RefUpdate};
 /// Maximum unconsumed events allowed per subscription.
 pub const MAX_PENDING_EVENTS: usize = 8192;
 
+/// Generator for process-unique [`CorrelationId`]s.
+static NEXT_CORRELATION_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+
+/// A short, process-unique id correlating structured log lines for a single unit of
+/// work (e.g. one patch revision moving through fetch, checkout, and upstream-tracking
+/// setup) so an operator can follow it across JSON log sinks, and across processes when
+/// threaded into an [`Event`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct CorrelationId(u64);
+
+impl CorrelationId {
+    /// Generate a new, process-unique correlation id.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self(NEXT_CORRELATION_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl std::fmt::Display for CorrelationId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:08x}", self.0)
+    }
+}
+
 /// A service event.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase", tag = "type")]
@@ -25,6 +51,10 @@ pub enum Event {
         remote: NodeId,
         rid: RepoId,
         updated: Vec<RefUpdate>,
+        /// Correlates this fetch with the structured log lines that triggered it, e.g.
+        /// a checkout's fetch span, when known.
+        #[serde(default)]
+        correlation_id: Option<CorrelationId>,
     },
     RefsSynced {
         remote: NodeId,
@@ -69,6 +99,19 @@ pub enum Event {
         features: node::Features,
         addresses: Vec<node::Address>,
     },
+    /// A patch's labels were changed locally, e.g. via `rad patch label`.
+    Labeled {
+        rid: RepoId,
+        patch_id: cob::patch::PatchId,
+        labels: Vec<cob::patch::Label>,
+    },
+    /// A background fetch job, e.g. one enqueued by a patch checkout, finished.
+    FetchCompleted {
+        rid: RepoId,
+        url: String,
+        oids: Vec<Oid>,
+        error: Option<String>,
+    },
     UploadPack(upload_pack::UploadPack),
 }
 
@@ -79,19 +122,29 @@ impl From<upload_pack::UploadPack> for Event {
 }
 
 /// Events feed.
-pub struct Events(chan::Receiver<Event>);
+pub struct Events(Subscription<Event>);
 
 impl IntoIterator for Events {
     type Item = Event;
     type IntoIter = chan::IntoIter<Event>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.0.into_iter()
+        self.0.receiver.into_iter()
     }
 }
 
 impl From<chan::Receiver<Event>> for Events {
     fn from(value: chan::Receiver<Event>) -> Self {
+        Self(Subscription {
+            receiver: value,
+            lagged: Arc::new(AtomicU64::new(0)),
+            _alive: Arc::new(()),
+        })
+    }
+}
+
+impl From<Subscription<Event>> for Events {
+    fn from(value: Subscription<Event>) -> Self {
         Self(value)
     }
 }
@@ -100,7 +153,7 @@ impl Deref for Events {
     type Target = chan::Receiver<Event>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.0.receiver
     }
 }
 
@@ -134,12 +187,127 @@ impl Events {
             }
         }
     }
+
+    /// Number of events dropped by the emitter under [`Overflow::Lag`] since this was
+    /// last called. Callers that can't afford to miss events should check this after
+    /// each read and, on a non-zero result, re-fetch the state they may have missed
+    /// rather than trust the stream alone.
+    pub fn lagged(&self) -> u64 {
+        self.0.lagged()
+    }
+}
+
+/// What to do when a subscriber's channel is full and the emitter has a new event to
+/// deliver to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Drop the incoming event and keep what's already queued. The subscriber stays
+    /// subscribed. This is the default.
+    #[default]
+    DropNewest,
+    /// Make room by dropping the oldest queued event, so the most recent event is always
+    /// kept. Queued events are still received oldest first.
+    DropOldest,
+    /// Drop the incoming event, but count it, so the subscriber can detect the gap via
+    /// [`Subscription::lagged`] and recover (e.g. by re-fetching state), like a
+    /// broadcast channel.
+    Lag,
+}
+
+/// A subscription handle returned by [`Emitter::subscribe`].
+pub struct Subscription<T> {
+    receiver: chan::Receiver<T>,
+    lagged: Arc<AtomicU64>,
+    /// Kept alive only for as long as this subscription exists. The matching `Weak` in
+    /// the `Emitter`'s `Subscriber` is how `emit` notices this subscription was
+    /// dropped. This can't be the channel's own receiver-count bookkeeping, because
+    /// `Subscriber::drain` (kept around for `DropOldest`) holds a second `Receiver`
+    /// clone that would keep the channel "connected" forever regardless of whether
+    /// this handle is still around.
+    _alive: Arc<()>,
+}
+
+impl<T> Subscription<T> {
+    /// Number of events dropped under [`Overflow::Lag`] since this was last called.
+    /// Always zero for subscriptions using another policy.
+    pub fn lagged(&self) -> u64 {
+        self.lagged.swap(0, Ordering::Relaxed)
+    }
+}
+
+impl<T> Deref for Subscription<T> {
+    type Target = chan::Receiver<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.receiver
+    }
+}
+
+impl<T> IntoIterator for Subscription<T> {
+    type Item = T;
+    type IntoIter = chan::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.receiver.into_iter()
+    }
+}
+
+/// A single subscriber, tracked internally by the [`Emitter`].
+struct Subscriber<T> {
+    policy: Overflow,
+    sender: chan::Sender<T>,
+    /// A second handle onto the subscriber's own channel, used only to drop the oldest
+    /// queued event under [`Overflow::DropOldest`]. Note this means the channel's own
+    /// receiver-count can never reach zero while this `Subscriber` is alive, so
+    /// `chan::TrySendError::Disconnected` is unreliable here — see `alive` instead.
+    drain: chan::Receiver<T>,
+    lagged: Arc<AtomicU64>,
+    /// Weak handle to the matching [`Subscription`]'s `_alive` marker. Once it can no
+    /// longer be upgraded, the subscription was dropped and this subscriber should be
+    /// torn down on the next emit, regardless of what the channel itself reports.
+    alive: std::sync::Weak<()>,
+}
+
+impl<T: Clone> Subscriber<T> {
+    /// Deliver `event` per this subscriber's policy. Returns `false` if the matching
+    /// [`Subscription`] has been dropped and this subscriber should be torn down.
+    fn send(&self, event: T) -> bool {
+        if self.alive.upgrade().is_none() {
+            return false;
+        }
+
+        match self.sender.try_send(event) {
+            Ok(()) | Err(chan::TrySendError::Disconnected(_)) => true,
+            Err(chan::TrySendError::Full(event)) => {
+                match self.policy {
+                    Overflow::DropNewest => {}
+                    Overflow::DropOldest => {
+                        // Make room for the new event by dropping the oldest one. If a
+                        // concurrent read on the subscriber's end beat us to it, the
+                        // channel already has room and this is a no-op.
+                        let _ = self.drain.try_recv();
+                        let _ = self.sender.try_send(event);
+                    }
+                    Overflow::Lag => {
+                        self.lagged.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                true
+            }
+        }
+    }
 }
 
 /// Publishes events to subscribers.
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct Emitter<T> {
-    subscribers: Arc<Mutex<Vec<chan::Sender<T>>>>,
+    subscribers: Arc<Mutex<Vec<Subscriber<T>>>>,
+}
+
+impl<T> std::fmt::Debug for Emitter<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Emitter").finish_non_exhaustive()
+    }
 }
 
 impl<T> Default for Emitter<T> {
@@ -151,26 +319,46 @@ impl<T> Default for Emitter<T> {
 }
 
 impl<T: Clone> Emitter<T> {
-    /// Emit event to subscribers and drop those who can't receive it.
-    /// Nb. subscribers are also dropped if their channel is full.
-    pub fn emit(&self, event: T) {
+    /// Emit event to subscribers, applying each subscriber's overflow policy
+    /// independently so that one full channel can't stall the others. Subscribers that
+    /// have disconnected are dropped.
+    pub fn emit(&self, event: T)
+    where
+        T: std::fmt::Debug,
+    {
+        let subscribers = self.subscriptions();
+        let _enter = tracing::trace_span!("emit", event = ?event, subscribers).entered();
+
         // SAFETY: We deliberately propagate panics from other threads holding the lock.
         #[allow(clippy::unwrap_used)]
         self.subscribers
             .lock()
             .unwrap()
-            .retain(|s| s.try_send(event.clone()).is_ok());
+            .retain(|sub| sub.send(event.clone()));
     }
 
-    /// Subscribe to events stream.
-    pub fn subscribe(&self) -> chan::Receiver<T> {
+    /// Subscribe to the events stream, applying `policy` when this subscriber falls
+    /// behind.
+    pub fn subscribe(&self, policy: Overflow) -> Subscription<T> {
         let (sender, receiver) = chan::bounded(MAX_PENDING_EVENTS);
+        let lagged = Arc::new(AtomicU64::new(0));
+        let alive = Arc::new(());
         // SAFETY: We deliberately propagate panics from other threads holding the lock.
         #[allow(clippy::unwrap_used)]
         let mut subs = self.subscribers.lock().unwrap();
-        subs.push(sender);
+        subs.push(Subscriber {
+            policy,
+            sender,
+            drain: receiver.clone(),
+            lagged: lagged.clone(),
+            alive: Arc::downgrade(&alive),
+        });
 
-        receiver
+        Subscription {
+            receiver,
+            lagged,
+            _alive: alive,
+        }
     }
 
     /// Number of subscribers.
@@ -188,7 +376,110 @@ impl<T: Clone> Emitter<T> {
             .lock()
             .unwrap()
             .iter()
-            .map(|ch| ch.len())
+            .map(|sub| sub.sender.len())
             .sum()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every subscription's channel is bounded at `MAX_PENDING_EVENTS`, so the tests
+    /// below emit one event more than that to trigger the overflow policies.
+    fn emitter() -> Emitter<u32> {
+        Emitter::default()
+    }
+
+    #[test]
+    fn drop_newest_keeps_the_oldest_queued_event() {
+        let emitter = emitter();
+        let sub = emitter.subscribe(Overflow::DropNewest);
+
+        for n in 0..MAX_PENDING_EVENTS as u32 + 1 {
+            emitter.emit(n);
+        }
+
+        assert_eq!(sub.try_recv(), Ok(0));
+        assert_eq!(sub.lagged(), 0);
+    }
+
+    #[test]
+    fn drop_oldest_keeps_the_newest_event() {
+        let emitter = emitter();
+        let sub = emitter.subscribe(Overflow::DropOldest);
+
+        for n in 0..MAX_PENDING_EVENTS as u32 + 1 {
+            emitter.emit(n);
+        }
+
+        assert_eq!(sub.try_recv(), Ok(1));
+        assert_eq!(sub.lagged(), 0);
+    }
+
+    #[test]
+    fn lag_drops_the_event_but_counts_it() {
+        let emitter = emitter();
+        let sub = emitter.subscribe(Overflow::Lag);
+
+        for n in 0..MAX_PENDING_EVENTS as u32 + 1 {
+            emitter.emit(n);
+        }
+
+        assert_eq!(sub.try_recv(), Ok(0));
+        assert_eq!(sub.lagged(), 1);
+        // Reading `lagged()` resets the counter.
+        assert_eq!(sub.lagged(), 0);
+    }
+
+    #[test]
+    fn a_disconnected_subscriber_is_dropped_on_the_next_emit() {
+        let emitter = emitter();
+        let sub = emitter.subscribe(Overflow::DropNewest);
+        drop(sub);
+
+        emitter.emit(1);
+
+        assert_eq!(emitter.subscriptions(), 0);
+    }
+
+    // The three tests below exercise the same cleanup path under each policy
+    // specifically: `Subscriber::drain` holds a second `Receiver` clone for the whole
+    // time any subscriber is registered (it is only read from under `DropOldest`), so
+    // the channel's own receiver count never reaches zero on drop. If cleanup were still
+    // keyed off `chan::TrySendError::Disconnected`, none of the cases here would be
+    // reaped, and emit would keep cloning events for subscribers nothing is reading.
+
+    #[test]
+    fn drop_newest_subscriber_is_cleaned_up_after_disconnect() {
+        let emitter = emitter();
+        let sub = emitter.subscribe(Overflow::DropNewest);
+        drop(sub);
+
+        emitter.emit(1);
+
+        assert_eq!(emitter.subscriptions(), 0);
+    }
+
+    #[test]
+    fn drop_oldest_subscriber_is_cleaned_up_after_disconnect() {
+        let emitter = emitter();
+        let sub = emitter.subscribe(Overflow::DropOldest);
+        drop(sub);
+
+        emitter.emit(1);
+
+        assert_eq!(emitter.subscriptions(), 0);
+    }
+
+    #[test]
+    fn lag_subscriber_is_cleaned_up_after_disconnect() {
+        let emitter = emitter();
+        let sub = emitter.subscribe(Overflow::Lag);
+        drop(sub);
+
+        emitter.emit(1);
+
+        assert_eq!(emitter.subscriptions(), 0);
+    }
+}
@@ -0,0 +1,358 @@
+//! Patch and issue activity feed, rendered as RSS/Atom, driven by the node's event stream.
+//!
+//! External tooling that can't speak the Radicle wire protocol (readers, dashboards) can
+//! instead follow a seed node by polling the files this module maintains. Interest is
+//! expressed with a small routing table of rules of the form `regex => [channel, ...]`:
+//! each incoming event's [`RepoId`] is rendered to its string form and matched against
+//! every rule's pattern, anchored to the full id. Every full-length match appends the
+//! event to the channels produced by substituting the pattern's capture groups into the
+//! channel name templates.
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::git::Oid;
+use crate::node::events::{Event, Events};
+use crate::prelude::*;
+
+/// A feed error.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("invalid route pattern {0:?}: {1}")]
+    Pattern(String, regex::Error),
+    #[error("i/o: {0}")]
+    Io(#[from] io::Error),
+    #[error("state file is corrupt: {0}")]
+    State(#[from] serde_json::Error),
+}
+
+/// A single routing rule: a pattern matched against a [`RepoId`]'s string form, and the
+/// channel name templates produced on a match, with capture groups substituted in.
+#[derive(Debug, Clone)]
+pub struct Route {
+    pattern: regex::Regex,
+    channels: Vec<String>,
+}
+
+impl Route {
+    /// Parse a route of the form `regex => [channel,...]`.
+    pub fn new(pattern: &str, channels: impl IntoIterator<Item = String>) -> Result<Self, Error> {
+        // Anchor the pattern so that only a full-length match of the repo id counts.
+        let anchored = format!("^(?:{pattern})$");
+        let pattern = regex::Regex::new(&anchored)
+            .map_err(|e| Error::Pattern(pattern.to_owned(), e))?;
+
+        Ok(Self {
+            pattern,
+            channels: channels.into_iter().collect(),
+        })
+    }
+
+    /// Return the channels this rule routes a repo id's string form (`subject`) to,
+    /// with capture groups expanded into the channel templates, or `None` if it
+    /// doesn't match.
+    fn route(&self, subject: &str) -> Option<Vec<String>> {
+        let captures = self.pattern.captures(subject)?;
+        let mut expanded = Vec::with_capacity(self.channels.len());
+
+        for channel in &self.channels {
+            let mut out = String::new();
+            captures.expand(channel, &mut out);
+            expanded.push(out);
+        }
+        Some(expanded)
+    }
+}
+
+/// A single entry rendered from an [`Event`].
+#[derive(Debug, Clone)]
+pub struct Item {
+    /// Stable identifier, so that restarts don't produce duplicate entries.
+    pub guid: Oid,
+    /// Repository the event concerns.
+    pub rid: RepoId,
+    /// Human-readable summary.
+    pub title: String,
+}
+
+impl Item {
+    fn rss(&self) -> String {
+        format!(
+            "<item><guid isPermaLink=\"false\">{}</guid><title>{}</title><description>{}</description></item>",
+            self.guid, self.rid, self.title
+        )
+    }
+
+    fn atom(&self) -> String {
+        format!(
+            "<entry><id>{}</id><title>{}</title><summary>{}</summary></entry>",
+            self.guid, self.rid, self.title
+        )
+    }
+}
+
+/// Feed format a channel is rendered as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Rss,
+    Atom,
+}
+
+impl Format {
+    /// Infer the format from a channel's file extension, defaulting to RSS.
+    fn of(channel: &str) -> Self {
+        if channel.ends_with(".atom") {
+            Self::Atom
+        } else {
+            Self::Rss
+        }
+    }
+}
+
+/// Per-channel progress, persisted so that a restart doesn't re-emit old items.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+struct State {
+    /// GUIDs already appended to each channel. A set, not just the last one seen,
+    /// because a channel can receive several distinct items from a single event (e.g.
+    /// one `RefsAnnounced` carrying several remotes' tips) and items don't necessarily arrive in
+    /// GUID order.
+    last_seen: HashMap<String, HashSet<Oid>>,
+}
+
+/// Generates activity feeds from the node's event stream.
+pub struct FeedGenerator {
+    routes: Vec<Route>,
+    /// Directory the per-channel feed files are written into.
+    out_dir: PathBuf,
+    /// Path to the persisted [`State`].
+    state_path: PathBuf,
+    state: Mutex<State>,
+}
+
+impl FeedGenerator {
+    /// Create a generator with the given routing table, writing feed files under
+    /// `out_dir` and persisting progress to `state_path`.
+    pub fn new(routes: Vec<Route>, out_dir: PathBuf, state_path: PathBuf) -> Result<Self, Error> {
+        let state = if state_path.exists() {
+            let bytes = fs::read(&state_path)?;
+            serde_json::from_slice(&bytes)?
+        } else {
+            State::default()
+        };
+
+        Ok(Self {
+            routes,
+            out_dir,
+            state_path,
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Consume events from `events` until the channel disconnects, appending matching
+    /// activity to the routed channels.
+    pub fn run(&self, events: Events) -> Result<(), Error> {
+        for event in events {
+            self.process(&event)?;
+        }
+        Ok(())
+    }
+
+    /// Process a single event, appending every item it renders to the channels it
+    /// routes to.
+    fn process(&self, event: &Event) -> Result<(), Error> {
+        for (rid, guid, title) in Self::render(event) {
+            let subject = rid.to_string();
+            for route in &self.routes {
+                let Some(channels) = route.route(&subject) else {
+                    continue;
+                };
+                for channel in channels {
+                    self.append(&channel, &Item { guid, rid, title: title.clone() })?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Derive the `(rid, guid, title)` triples for events this feed cares about. A
+    /// `RefsAnnounced` carries one signed-refs tip per remote, so it renders one item per
+    /// entry, not just the first.
+    fn render(event: &Event) -> Vec<(RepoId, Oid, String)> {
+        match event {
+            Event::RefsAnnounced { rid, refs, nid, .. } => refs
+                .iter()
+                .map(|r| (*rid, r.at, format!("{nid} announced a ref in {rid}")))
+                .collect(),
+            Event::RefsSynced { rid, remote, at } => {
+                vec![(*rid, *at, format!("{remote} synced refs in {rid}"))]
+            }
+            Event::LocalRefsAnnounced { rid, refs, .. } => {
+                vec![(*rid, refs.at, format!("local refs announced in {rid}"))]
+            }
+            Event::FetchCompleted {
+                rid,
+                oids,
+                error: None,
+                ..
+            } => oids
+                .iter()
+                .map(|oid| (*rid, *oid, format!("background fetch completed in {rid}")))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Append `item` to `channel`'s feed file, unless its GUID was already seen.
+    fn append(&self, channel: &str, item: &Item) -> Result<(), Error> {
+        // SAFETY: We deliberately propagate panics from other threads holding the lock.
+        #[allow(clippy::unwrap_used)]
+        let mut state = self.state.lock().unwrap();
+
+        let seen = state.last_seen.entry(channel.to_owned()).or_default();
+        if !seen.insert(item.guid) {
+            return Ok(());
+        }
+
+        let path = self.out_dir.join(channel);
+        let rendered = match Format::of(channel) {
+            Format::Rss => item.rss(),
+            Format::Atom => item.atom(),
+        };
+        Self::append_file(&path, &rendered)?;
+
+        fs::write(&self.state_path, serde_json::to_vec(&*state)?)?;
+
+        Ok(())
+    }
+
+    fn append_file(path: &Path, line: &str) -> io::Result<()> {
+        use std::io::Write;
+
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        writeln!(file, "{line}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rid() -> RepoId {
+        RepoId::from(Oid::from(crate::git::raw::Oid::zero()))
+    }
+
+    fn oid(n: u8) -> Oid {
+        let mut bytes = [0u8; 20];
+        bytes[19] = n;
+        Oid::from(crate::git::raw::Oid::from_bytes(&bytes).unwrap())
+    }
+
+    #[test]
+    fn anchors_the_pattern_to_the_full_subject() {
+        let route = Route::new("abc", ["out.atom".to_owned()]).unwrap();
+
+        assert_eq!(route.route("abc"), Some(vec!["out.atom".to_owned()]));
+        assert_eq!(route.route("xabc"), None);
+        assert_eq!(route.route("abcx"), None);
+    }
+
+    #[test]
+    fn expands_capture_groups_into_channel_templates() {
+        let route = Route::new("z([0-9]+)", ["repo-$1.atom".to_owned()]).unwrap();
+
+        assert_eq!(
+            route.route("z42"),
+            Some(vec!["repo-42.atom".to_owned()])
+        );
+    }
+
+    #[test]
+    fn append_dedupes_a_replayed_item_on_the_same_channel() {
+        let dir = tempfile::tempdir().unwrap();
+        let generator = FeedGenerator::new(
+            vec![],
+            dir.path().to_owned(),
+            dir.path().join("state.json"),
+        )
+        .unwrap();
+        let item = Item {
+            guid: oid(1),
+            rid: rid(),
+            title: "hello".to_owned(),
+        };
+
+        generator.append("out.rss", &item).unwrap();
+        generator.append("out.rss", &item).unwrap();
+
+        let contents = fs::read_to_string(dir.path().join("out.rss")).unwrap();
+        assert_eq!(contents.lines().count(), 1);
+    }
+
+    #[test]
+    fn append_does_not_dedupe_across_different_channels() {
+        let dir = tempfile::tempdir().unwrap();
+        let generator = FeedGenerator::new(
+            vec![],
+            dir.path().to_owned(),
+            dir.path().join("state.json"),
+        )
+        .unwrap();
+        let item = Item {
+            guid: oid(1),
+            rid: rid(),
+            title: "hello".to_owned(),
+        };
+
+        generator.append("a.rss", &item).unwrap();
+        generator.append("b.rss", &item).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.rss")).unwrap().lines().count(),
+            1
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("b.rss")).unwrap().lines().count(),
+            1
+        );
+    }
+
+    #[test]
+    fn append_does_not_dedupe_distinct_guids_on_the_same_channel() {
+        let dir = tempfile::tempdir().unwrap();
+        let generator = FeedGenerator::new(
+            vec![],
+            dir.path().to_owned(),
+            dir.path().join("state.json"),
+        )
+        .unwrap();
+
+        generator
+            .append(
+                "out.rss",
+                &Item {
+                    guid: oid(1),
+                    rid: rid(),
+                    title: "one".to_owned(),
+                },
+            )
+            .unwrap();
+        generator
+            .append(
+                "out.rss",
+                &Item {
+                    guid: oid(2),
+                    rid: rid(),
+                    title: "two".to_owned(),
+                },
+            )
+            .unwrap();
+
+        let contents = fs::read_to_string(dir.path().join("out.rss")).unwrap();
+        assert_eq!(contents.lines().count(), 2);
+    }
+}
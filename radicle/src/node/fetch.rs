@@ -0,0 +1,341 @@
+//! Background fetch worker with adaptive throughput smoothing.
+//!
+//! `checkout::find_patch_commit` used to fetch inline and block whenever a patch head
+//! was missing locally, which serializes and bursts under concurrent checkouts. This
+//! worker instead accepts fetch requests on a queue, coalesces overlapping ones, and
+//! paces execution against a rolling average of recent fetch durations so storage sees
+//! a smooth rate rather than a thundering herd.
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel as chan;
+
+use crate::git;
+use crate::git::Oid;
+use crate::identity::RepoId;
+use crate::node::events::{Emitter, Event};
+
+/// Result of a single fetch, as seen by a waiter.
+type FetchResult = Result<(), String>;
+
+/// A fetch error surfaced to a [`Handle`] waiter.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("fetch worker shut down before this request completed")]
+    Disconnected,
+    #[error("fetch failed: {0}")]
+    Fetch(String),
+}
+
+/// Worker tuning parameters.
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// Target number of fetches per second, averaged over `window` jobs.
+    pub target_rate_hz: f64,
+    /// Number of recent fetch durations kept for the rolling average.
+    pub window: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            target_rate_hz: 4.0,
+            window: 16,
+        }
+    }
+}
+
+/// A request to fetch a set of oids from `url` into the working copy at `working`.
+#[derive(Debug, Clone)]
+pub struct Request {
+    /// Repository this fetch is on behalf of, so completion can be attributed to it in
+    /// [`Event::FetchCompleted`].
+    pub rid: RepoId,
+    /// Path to the working copy's git directory to fetch into.
+    pub working: PathBuf,
+    /// Url to fetch from, e.g. a `file://` url for anonymous local fetches.
+    pub url: String,
+    /// Oids the caller is waiting on.
+    pub oids: Vec<Oid>,
+}
+
+/// Whether `a` and `b` fetch into the same working copy from the same remote, and so
+/// can share a single underlying fetch.
+fn same_target(a: &Request, b: &Request) -> bool {
+    a.working == b.working && a.url == b.url
+}
+
+/// A handle to a pending or in-flight fetch, returned by [`Worker::enqueue`].
+pub struct Handle {
+    receiver: chan::Receiver<FetchResult>,
+}
+
+impl Handle {
+    /// Block until the fetch this handle was issued for completes.
+    pub fn wait(self) -> Result<(), Error> {
+        match self.receiver.recv() {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(Error::Fetch(e)),
+            Err(_) => Err(Error::Disconnected),
+        }
+    }
+}
+
+struct Job {
+    request: Request,
+    waiters: Vec<chan::Sender<FetchResult>>,
+}
+
+struct State {
+    queue: VecDeque<Job>,
+    /// The job the background thread is actively fetching, if any. Tracked so
+    /// `enqueue` can also coalesce against it, not just against `queue`.
+    current: Option<Job>,
+    /// Sliding window of the last `Config::window` fetch durations.
+    durations: VecDeque<Duration>,
+    /// Current pacing interval, exposed for metrics.
+    sleep_interval: Duration,
+    shutdown: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    cond: Condvar,
+}
+
+/// Background fetch worker. Dropping the worker signals the background thread to exit
+/// once its current job finishes.
+pub struct Worker {
+    shared: Arc<Shared>,
+    config: Config,
+    _thread: thread::JoinHandle<()>,
+}
+
+impl Worker {
+    /// Spawn a worker, emitting a [`Event::FetchCompleted`] on `emitter` after every job.
+    pub fn spawn(emitter: Emitter<Event>, config: Config) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                current: None,
+                durations: VecDeque::with_capacity(config.window),
+                sleep_interval: Duration::ZERO,
+                shutdown: false,
+            }),
+            cond: Condvar::new(),
+        });
+        let worker_shared = shared.clone();
+        let thread = thread::spawn(move || Self::main(worker_shared, emitter, config));
+
+        Self {
+            shared,
+            config,
+            _thread: thread,
+        }
+    }
+
+    /// Enqueue a fetch request, returning a handle to wait on its completion. A request
+    /// for the same working copy and url as one already queued is merged into it and
+    /// shares its completion, rather than triggering a second fetch.
+    ///
+    /// A request matching the job currently being fetched is also coalesced onto it,
+    /// but only if all of its oids are already covered by that in-flight job: the
+    /// in-flight fetch's refspecs were already handed to git, so adding an oid to its
+    /// `Request` at this point wouldn't actually fetch it this round. When the oids
+    /// aren't fully covered, a new job is queued for the next round instead.
+    pub fn enqueue(&self, request: Request) -> Handle {
+        let (sender, receiver) = chan::bounded(1);
+        // SAFETY: We deliberately propagate panics from other threads holding the lock.
+        #[allow(clippy::unwrap_used)]
+        let mut state = self.shared.state.lock().unwrap();
+
+        if let Some(job) = state
+            .queue
+            .iter_mut()
+            .find(|job| same_target(&job.request, &request))
+        {
+            for oid in request.oids {
+                if !job.request.oids.contains(&oid) {
+                    job.request.oids.push(oid);
+                }
+            }
+            job.waiters.push(sender);
+        } else if let Some(job) = state.current.as_mut().filter(|job| {
+            same_target(&job.request, &request)
+                && request.oids.iter().all(|oid| job.request.oids.contains(oid))
+        }) {
+            job.waiters.push(sender);
+        } else {
+            state.queue.push_back(Job {
+                request,
+                waiters: vec![sender],
+            });
+        }
+        self.shared.cond.notify_one();
+
+        Handle { receiver }
+    }
+
+    /// Number of fetch jobs currently queued, for metrics. The job in flight, if any,
+    /// is not counted.
+    pub fn depth(&self) -> usize {
+        // SAFETY: We deliberately propagate panics from other threads holding the lock.
+        #[allow(clippy::unwrap_used)]
+        self.shared.state.lock().unwrap().queue.len()
+    }
+
+    /// The worker's current pacing interval, for metrics.
+    pub fn sleep_interval(&self) -> Duration {
+        // SAFETY: We deliberately propagate panics from other threads holding the lock.
+        #[allow(clippy::unwrap_used)]
+        self.shared.state.lock().unwrap().sleep_interval
+    }
+
+    /// The tuning parameters this worker was spawned with.
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    fn main(shared: Arc<Shared>, emitter: Emitter<Event>, config: Config) {
+        loop {
+            let snapshot = {
+                // SAFETY: We deliberately propagate panics from other threads holding the lock.
+                #[allow(clippy::unwrap_used)]
+                let mut state = shared.state.lock().unwrap();
+                let job = loop {
+                    if state.shutdown {
+                        return;
+                    }
+                    if let Some(job) = state.queue.pop_front() {
+                        break job;
+                    }
+                    // SAFETY: We deliberately propagate panics from other threads holding the lock.
+                    #[allow(clippy::unwrap_used)]
+                    {
+                        state = shared.cond.wait(state).unwrap();
+                    }
+                };
+                let snapshot = job.request.clone();
+                // Parked here, not fetched from, so a concurrent `enqueue` can still see
+                // which oids this round actually covers.
+                state.current = Some(job);
+                snapshot
+            };
+
+            let start = Instant::now();
+            let result = Self::fetch(&snapshot);
+            let elapsed = start.elapsed();
+
+            let (job, sleep_for) = {
+                // SAFETY: We deliberately propagate panics from other threads holding the lock.
+                #[allow(clippy::unwrap_used)]
+                let mut state = shared.state.lock().unwrap();
+                // SAFETY: set to `Some` before this job's fetch started, and nothing
+                // else clears it.
+                #[allow(clippy::unwrap_used)]
+                let job = state.current.take().unwrap();
+
+                if state.durations.len() == config.window.max(1) {
+                    state.durations.pop_front();
+                }
+                state.durations.push_back(elapsed);
+
+                let sleep_for = pace(&state.durations, config.target_rate_hz);
+                state.sleep_interval = sleep_for;
+
+                (job, sleep_for)
+            };
+
+            // Notify before pacing: a waiter's `handle.wait()` (and feed/notify
+            // subscribers watching `FetchCompleted`) shouldn't sit behind the sleep
+            // that's meant to space out *this thread's next* fetch, not delay the one
+            // that just finished.
+            emitter.emit(Event::FetchCompleted {
+                rid: job.request.rid,
+                url: job.request.url.clone(),
+                oids: job.request.oids.clone(),
+                error: result.as_ref().err().cloned(),
+            });
+            for waiter in job.waiters {
+                let _ = waiter.send(result.clone());
+            }
+
+            if !sleep_for.is_zero() {
+                thread::sleep(sleep_for);
+            }
+        }
+    }
+
+    fn fetch(request: &Request) -> FetchResult {
+        let working = git::raw::Repository::open(&request.working).map_err(|e| e.to_string())?;
+        let refspecs = request
+            .oids
+            .iter()
+            .map(|oid| oid.to_string())
+            .collect::<Vec<_>>();
+
+        working
+            .remote_anonymous(request.url.as_str())
+            .and_then(|mut remote| remote.fetch(&refspecs, None, None))
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        // SAFETY: We deliberately propagate panics from other threads holding the lock.
+        #[allow(clippy::unwrap_used)]
+        {
+            self.shared.state.lock().unwrap().shutdown = true;
+        }
+        self.shared.cond.notify_all();
+    }
+}
+
+/// How long to sleep before the next fetch so that, averaged over `durations`, fetches
+/// happen at `target_rate_hz`. Returns zero once the recent average already exceeds the
+/// target interval, rather than trying to "catch up".
+fn pace(durations: &VecDeque<Duration>, target_rate_hz: f64) -> Duration {
+    if durations.is_empty() {
+        return Duration::ZERO;
+    }
+    let average = durations.iter().sum::<Duration>() / durations.len() as u32;
+    let target = Duration::from_secs_f64(1.0 / target_rate_hz.max(f64::EPSILON));
+
+    target.saturating_sub(average)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sleeps_the_remainder_of_the_target_interval() {
+        let durations: VecDeque<_> = [Duration::from_millis(50)].into_iter().collect();
+        // 4 Hz => a 250ms cycle; a 50ms average fetch leaves 200ms to sleep.
+        assert_eq!(pace(&durations, 4.0), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn does_not_sleep_once_fetches_are_slower_than_the_target_interval() {
+        let durations: VecDeque<_> = [Duration::from_millis(500)].into_iter().collect();
+        assert_eq!(pace(&durations, 4.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn averages_over_the_whole_window_not_just_the_last_fetch() {
+        let durations: VecDeque<_> = [Duration::from_millis(0), Duration::from_millis(200)]
+            .into_iter()
+            .collect();
+        // Average is 100ms; 4 Hz leaves 150ms to sleep.
+        assert_eq!(pace(&durations, 4.0), Duration::from_millis(150));
+    }
+
+    #[test]
+    fn an_empty_window_does_not_sleep() {
+        assert_eq!(pace(&VecDeque::new(), 4.0), Duration::ZERO);
+    }
+}
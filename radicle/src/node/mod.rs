@@ -0,0 +1,4 @@
+pub mod events;
+pub mod feed;
+pub mod fetch;
+pub mod notify;
@@ -0,0 +1,386 @@
+//! Email notifications for patch and ref activity.
+//!
+//! Mirrors a post-receive email hook, but driven by the node's own event stream rather
+//! than git hooks. Configuration names a transport (SMTP, or a sendmail-style command),
+//! a from-address, and per-repository recipient lists; each meaningful event is rendered
+//! into a subject and body and handed to the transport.
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::process::{Command, Stdio};
+
+use anyhow::bail;
+
+use crate::cob::patch::Label;
+use crate::git::Oid;
+use crate::node::events::{Event, Events};
+use crate::prelude::*;
+use crate::storage::RefUpdate;
+
+/// Where a notifier hands off a rendered email.
+#[derive(Debug, Clone)]
+pub enum Transport {
+    /// Run a sendmail-style command, piping the message to its stdin. The first
+    /// element is the program, the rest are its arguments, e.g.
+    /// `vec!["/usr/sbin/sendmail".into(), "-t".into()]` — unlike a single command
+    /// string, this doesn't require guessing at shell-word splitting rules.
+    Sendmail(Vec<String>),
+    /// Submit over SMTP, speaking the minimal EHLO/MAIL/RCPT/DATA dialogue.
+    Smtp { host: String, port: u16 },
+}
+
+/// Notifier configuration.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub from: String,
+    pub transport: Transport,
+    /// Recipients subscribed to activity on a given repository.
+    pub recipients: HashMap<RepoId, Vec<String>>,
+}
+
+/// A rendered notification, ready to hand to the transport.
+struct Message {
+    subject: String,
+    body: String,
+}
+
+/// Consumes the node's event stream and emails meaningful patch activity.
+pub struct Notifier {
+    config: Config,
+}
+
+impl Notifier {
+    pub fn new(config: Config) -> Self {
+        Self { config }
+    }
+
+    /// Consume `events` until the channel disconnects, sending email for activity that
+    /// has recipients configured.
+    ///
+    /// The emitter may drop events for us under `Overflow::Lag` rather than block on a slow mail
+    /// transport, so after every event we check [`Events::lagged`] and, if we missed
+    /// any, warn recipients instead of silently under-reporting activity.
+    pub fn run(&self, events: Events) -> anyhow::Result<()> {
+        while let Ok(event) = events.recv() {
+            if let Some((rid, message)) = self.render(&event) {
+                self.notify(rid, message)?;
+            }
+
+            let lagged = events.lagged();
+            if lagged > 0 {
+                self.notify_lagged(lagged)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Render `event` into a notification, if it's one we notify on.
+    ///
+    /// `Event::RefsAnnounced` is deliberately not rendered here: `refs::RefsAt` carries
+    /// no ref name, only a remote's `(NodeId, Oid)` tip, so there's no way to tell
+    /// whether the announcement even touches a patch without guessing. Reporting every
+    /// announcement as "new patch activity" was spammy and often wrong; we wait for the
+    /// actual fetch to know.
+    fn render(&self, event: &Event) -> Option<(RepoId, Message)> {
+        match event {
+            Event::RefsFetched {
+                rid,
+                remote,
+                updated,
+                ..
+            } => {
+                let patches = updated.iter().filter_map(patch_update).collect::<Vec<_>>();
+                if patches.is_empty() {
+                    return None;
+                }
+                let body = patches
+                    .iter()
+                    .map(|(patch_id, oid)| format!("- patch {patch_id} is now at {oid}"))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+
+                Some((
+                    *rid,
+                    Message {
+                        subject: format!("[{rid}] {} patch ref(s) fetched", patches.len()),
+                        body: format!("Fetched from {remote}:\n{body}"),
+                    },
+                ))
+            }
+            Event::Labeled {
+                rid,
+                patch_id,
+                labels,
+            } => Some((
+                *rid,
+                Message {
+                    subject: format!("[{rid}] patch {patch_id} labels changed"),
+                    body: format!("Labels are now: {}.", format_labels(labels)),
+                },
+            )),
+            // Only notify on failure: a successful background fetch isn't activity
+            // worth emailing about, but a failed one may need an operator's attention.
+            Event::FetchCompleted {
+                rid,
+                url,
+                error: Some(error),
+                ..
+            } => Some((
+                *rid,
+                Message {
+                    subject: format!("[{rid}] background fetch failed"),
+                    body: format!("Fetch from {url} failed: {error}"),
+                },
+            )),
+            _ => None,
+        }
+    }
+
+    /// Notify the recipients of every configured repository that the stream skipped `n`
+    /// events, since the skipped events' repositories aren't known.
+    fn notify_lagged(&self, n: u64) -> anyhow::Result<()> {
+        for (rid, recipients) in &self.config.recipients {
+            let message = Message {
+                subject: format!("[{rid}] missed {n} event(s)"),
+                body: format!(
+                    "This node's notifier fell behind and dropped {n} event(s). Some \
+                     activity on {rid} may not have been reported; check the node's \
+                     event log or storage directly."
+                ),
+            };
+            self.send(recipients, &message)?;
+        }
+        Ok(())
+    }
+
+    fn notify(&self, rid: RepoId, message: Message) -> anyhow::Result<()> {
+        let Some(recipients) = self.config.recipients.get(&rid) else {
+            return Ok(());
+        };
+        self.send(recipients, &message)
+    }
+
+    /// Render the RFC822 message and hand it to the configured transport.
+    ///
+    /// `message.subject` and `message.body` may echo repo-controlled content (patch
+    /// labels set via `rad patch label`, fetch error text), so both are sanitized
+    /// before being interpolated into the raw message: a `\r` or `\n` in the subject
+    /// would otherwise inject arbitrary extra headers (or a second message) into the
+    /// outgoing mail.
+    fn send(&self, recipients: &[String], message: &Message) -> anyhow::Result<()> {
+        let raw = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\n\r\n{}\r\n",
+            sanitize_header(&self.config.from),
+            recipients
+                .iter()
+                .map(|r| sanitize_header(r))
+                .collect::<Vec<_>>()
+                .join(", "),
+            sanitize_header(&message.subject),
+            sanitize_body(&message.body),
+        );
+
+        match &self.config.transport {
+            Transport::Sendmail(command) => {
+                let [program, args @ ..] = command.as_slice() else {
+                    bail!("sendmail transport command is empty");
+                };
+                let mut child = Command::new(program)
+                    .args(args)
+                    .args(recipients)
+                    .stdin(Stdio::piped())
+                    .spawn()?;
+                if let Some(mut stdin) = child.stdin.take() {
+                    stdin.write_all(raw.as_bytes())?;
+                }
+                child.wait()?;
+            }
+            Transport::Smtp { host, port } => {
+                smtp_send(host, *port, &self.config.from, recipients, &raw)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Strip CR/LF from a value interpolated into a header or an SMTP command line, so it
+/// can't inject extra headers, commands, or messages.
+fn sanitize_header(s: &str) -> String {
+    s.chars()
+        .map(|c| if c == '\r' || c == '\n' { ' ' } else { c })
+        .collect()
+}
+
+/// Normalize a value interpolated into the message body to LF-only line endings, so it
+/// can't smuggle a stray `\r\n.\r\n` that would prematurely end the SMTP `DATA` section.
+fn sanitize_body(s: &str) -> String {
+    s.replace('\r', "")
+}
+
+/// Speak the minimal EHLO/MAIL FROM/RCPT TO/DATA dialogue needed to submit `message`
+/// (already in raw RFC822 form, with `\r\n` line endings) to an SMTP server.
+fn smtp_send(
+    host: &str,
+    port: u16,
+    from: &str,
+    recipients: &[String],
+    message: &str,
+) -> anyhow::Result<()> {
+    let mut stream = TcpStream::connect((host, port))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    smtp_reply(&mut reader)?;
+    smtp_command(&mut stream, &mut reader, "EHLO localhost")?;
+    smtp_command(
+        &mut stream,
+        &mut reader,
+        &format!("MAIL FROM:<{}>", sanitize_header(from)),
+    )?;
+    for recipient in recipients {
+        smtp_command(
+            &mut stream,
+            &mut reader,
+            &format!("RCPT TO:<{}>", sanitize_header(recipient)),
+        )?;
+    }
+    smtp_command(&mut stream, &mut reader, "DATA")?;
+
+    // Dot-stuff: a line that starts with `.` gets an extra one prepended, so the
+    // server doesn't mistake it for the end-of-data marker.
+    for line in message.split("\r\n") {
+        if let Some(rest) = line.strip_prefix('.') {
+            stream.write_all(b".")?;
+            stream.write_all(rest.as_bytes())?;
+        } else {
+            stream.write_all(line.as_bytes())?;
+        }
+        stream.write_all(b"\r\n")?;
+    }
+    stream.write_all(b".\r\n")?;
+    smtp_reply(&mut reader)?;
+
+    smtp_command(&mut stream, &mut reader, "QUIT")?;
+    Ok(())
+}
+
+/// Send one command line and read its reply.
+fn smtp_command(
+    stream: &mut TcpStream,
+    reader: &mut BufReader<TcpStream>,
+    line: &str,
+) -> anyhow::Result<()> {
+    stream.write_all(line.as_bytes())?;
+    stream.write_all(b"\r\n")?;
+    smtp_reply(reader)
+}
+
+/// Read an SMTP reply, following multi-line continuations (`250-...`), and bail unless
+/// its status code is 2xx or 3xx.
+fn smtp_reply(reader: &mut BufReader<TcpStream>) -> anyhow::Result<()> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            bail!("SMTP server closed the connection unexpectedly");
+        }
+        let code: u16 = line.get(..3).and_then(|c| c.parse().ok()).unwrap_or(0);
+        if !(200..400).contains(&code) {
+            bail!("SMTP server rejected the command: {}", line.trim_end());
+        }
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
+
+/// If `update` touches the patch refs namespace (`refs/heads/patches/<id>`), the patch
+/// id and the oid the ref now points to (its old oid, for a deletion).
+fn patch_update(update: &RefUpdate) -> Option<(&str, Oid)> {
+    let patch_id = ref_name(update).strip_prefix("refs/heads/patches/")?;
+    Some((patch_id, ref_oid(update)))
+}
+
+fn ref_name(update: &RefUpdate) -> &str {
+    match update {
+        RefUpdate::Updated { name, .. }
+        | RefUpdate::Created { name, .. }
+        | RefUpdate::Deleted { name, .. }
+        | RefUpdate::Skipped { name, .. } => name.as_str(),
+    }
+}
+
+fn ref_oid(update: &RefUpdate) -> Oid {
+    match update {
+        RefUpdate::Updated { new, .. } => *new,
+        RefUpdate::Created { oid, .. } | RefUpdate::Deleted { oid, .. } | RefUpdate::Skipped { oid, .. } => *oid,
+    }
+}
+
+fn format_labels(labels: &[Label]) -> String {
+    if labels.is_empty() {
+        return "(none)".to_owned();
+    }
+    labels
+        .iter()
+        .map(|l| l.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::RefString;
+
+    fn oid(n: u8) -> Oid {
+        let mut bytes = [0u8; 20];
+        bytes[19] = n;
+        Oid::from(crate::git::raw::Oid::from_bytes(&bytes).unwrap())
+    }
+
+    #[test]
+    fn sanitize_header_strips_cr_and_lf() {
+        let injected = "ok\r\nBcc: everyone@example.com";
+        let sanitized = sanitize_header(injected);
+
+        assert!(!sanitized.contains('\r'));
+        assert!(!sanitized.contains('\n'));
+        assert_eq!(sanitized, "ok  Bcc: everyone@example.com");
+    }
+
+    #[test]
+    fn sanitize_body_strips_cr_but_keeps_newlines() {
+        let injected = "line one\r\n.\r\nQUIT\r\n";
+        let sanitized = sanitize_body(injected);
+
+        assert!(!sanitized.contains('\r'));
+        assert_eq!(sanitized, "line one\n.\nQUIT\n");
+    }
+
+    #[test]
+    fn patch_update_extracts_the_patch_id_from_a_matching_ref() {
+        let target = oid(7);
+        let update = RefUpdate::Created {
+            name: RefString::try_from("refs/heads/patches/abc123").unwrap(),
+            oid: target,
+        };
+
+        assert_eq!(patch_update(&update), Some(("abc123", target)));
+    }
+
+    #[test]
+    fn patch_update_ignores_non_patch_refs() {
+        let update = RefUpdate::Created {
+            name: RefString::try_from("refs/heads/main").unwrap(),
+            oid: oid(1),
+        };
+
+        assert_eq!(patch_update(&update), None);
+    }
+
+    #[test]
+    fn format_labels_reports_none_when_empty() {
+        let labels: Vec<Label> = vec![];
+        assert_eq!(format_labels(&labels), "(none)");
+    }
+}